@@ -3,7 +3,9 @@
 
 extern crate petgraph;
 
-use std::collections::{HashSet, HashMap, LinkedList};
+use std::collections::{HashMap, HashSet, LinkedList};
+use petgraph::algo::has_path_connecting;
+use petgraph::visit::{EdgeFiltered, EdgeRef};
 use petgraph::Graph;
 use petgraph::prelude::{NodeIndex, Incoming};
 use petgraph::visit::{depth_first_search, DfsEvent, Control};
@@ -16,11 +18,26 @@ enum AssetType {
   HTML
 }
 
+// How an asset must be bundled relative to the code that depends on it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum BundleBehavior {
+  // No special handling; follows the normal bundling rules.
+  None,
+  // Merged directly into the referencing bundle and never emitted as a
+  // standalone file (e.g. CSS text inlined into a JS string).
+  Inline,
+  // Must not share code with its referencing bundle (e.g. a web worker or
+  // a separate HTML entry point). Duplicated into each isolated bundle
+  // that reaches it rather than hoisted into a shared bundle.
+  Isolated
+}
+
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct Asset<'a> {
   name: &'a str,
   asset_type: AssetType,
-  size: usize
+  size: usize,
+  bundle_behavior: BundleBehavior
 }
 
 #[derive(Debug)]
@@ -28,11 +45,94 @@ struct Dependency {
   is_async: bool
 }
 
+// Whether a dependency that caused a bundle boundary is loaded in parallel
+// with its parent, fetched later via a dynamic import, or duplicated into an
+// isolated bundle that must be loaded on its own (e.g. a worker).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReferenceKind {
+  Sync,
+  Async,
+  Isolated
+}
+
+// Records, for every dependency that caused a bundle boundary, which bundle
+// it ultimately resolves to, so a packager can rewrite each import site.
+#[derive(Debug, Default)]
+struct DependencyBundleGraph {
+  references: Vec<(NodeIndex, NodeIndex, NodeIndex, ReferenceKind)>
+}
+
+impl DependencyBundleGraph {
+  fn add_reference(&mut self, u: NodeIndex, v: NodeIndex, bundle_id: NodeIndex, kind: ReferenceKind) {
+    self.references.push((u, v, bundle_id, kind));
+  }
+
+  // Repoints references that resolved to `from` so they resolve to `to`
+  // instead, e.g. when an async bundle is internalized into its parent.
+  fn retarget(&mut self, from: NodeIndex, to: NodeIndex) {
+    for reference in &mut self.references {
+      if reference.2 == from {
+        reference.2 = to;
+      }
+    }
+  }
+}
+
 #[derive(Debug, Default)]
 struct Bundle {
   asset_ids: Vec<NodeIndex>,
   size: usize,
-  source_bundles: Vec<NodeIndex>
+  source_bundles: Vec<NodeIndex>,
+  // Async dependency targets that turned out to already be guaranteed
+  // loaded wherever this bundle loads, so no separate bundle was created
+  // for them. Packagers should rewrite the corresponding dynamic `import()`
+  // to resolve against this bundle instead of fetching a new one.
+  internalized_asset_ids: Vec<NodeIndex>,
+  // Assets merged into this bundle because of `BundleBehavior::Inline`.
+  // These should not be emitted as their own file.
+  inline_asset_ids: Vec<NodeIndex>
+}
+
+// Which HTTP version the output bundles will be served over. HTTP/1
+// connections are expensive, HTTP/2 supports cheap multiplexed requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HttpVersion {
+  Http1,
+  Http2
+}
+
+// Tunable parameters for the bundling algorithm.
+#[derive(Debug, Clone, Copy)]
+struct BundlerConfig {
+  // Minimum number of distinct bundle groups a shared bundle must be
+  // referenced from in order to be kept rather than merged back into its
+  // source bundles.
+  min_bundles: usize,
+  // Minimum size (in bytes) a shared bundle must reach in order to be kept
+  // rather than merged back into its source bundles.
+  min_bundle_size: usize,
+  // Maximum number of bundles allowed to load in parallel within a single
+  // bundle group before the smallest ones are merged back into their
+  // sources.
+  max_parallel_requests: usize
+}
+
+impl BundlerConfig {
+  // Picks sensible defaults for the given HTTP version.
+  fn for_http_version(http_version: HttpVersion) -> Self {
+    match http_version {
+      HttpVersion::Http1 => BundlerConfig {
+        min_bundles: 1,
+        min_bundle_size: 30000,
+        max_parallel_requests: 6
+      },
+      HttpVersion::Http2 => BundlerConfig {
+        min_bundles: 1,
+        min_bundle_size: 20000,
+        max_parallel_requests: 25
+      }
+    }
+  }
 }
 
 impl Bundle {
@@ -40,26 +140,90 @@ impl Bundle {
     Bundle {
       asset_ids: vec![asset_id],
       size: asset.size,
-      source_bundles: vec![]
+      source_bundles: vec![],
+      internalized_asset_ids: vec![],
+      inline_asset_ids: vec![]
     }
   }
 }
 
+// A fixed-width set of small integers backed by 64-bit words, used to track
+// which bundle roots (by dense index) a node is reachable from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+struct BitSet(Vec<u64>);
+
+impl BitSet {
+  fn new(bits: usize) -> Self {
+    BitSet(vec![0u64; bits.div_ceil(64)])
+  }
+
+  fn singleton(bits: usize, index: usize) -> Self {
+    let mut set = BitSet::new(bits);
+    set.insert(index);
+    set
+  }
+
+  fn insert(&mut self, index: usize) {
+    self.0[index / 64] |= 1u64 << (index % 64);
+  }
+
+  fn remove(&mut self, index: usize) {
+    self.0[index / 64] &= !(1u64 << (index % 64));
+  }
+
+  fn is_empty(&self) -> bool {
+    self.0.iter().all(|word| *word == 0)
+  }
+
+  fn contains(&self, index: usize) -> bool {
+    self.0[index / 64] & (1u64 << (index % 64)) != 0
+  }
+
+  fn intersects(&self, other: &BitSet) -> bool {
+    self.0.iter().zip(&other.0).any(|(a, b)| a & b != 0)
+  }
+
+  fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+    let len = self.0.len();
+    (0..len * 64).filter(move |i| (self.0[*i / 64] >> (*i % 64)) & 1 == 1)
+  }
+}
+
+// Assigns the next dense root index to `node`, recording it in both the
+// lookup map and the index -> node table.
+fn assign_root_index(root_ids: &mut Vec<NodeIndex>, root_index: &mut HashMap<NodeIndex, usize>, node: NodeIndex) -> usize {
+  let idx = root_ids.len();
+  root_ids.push(node);
+  root_index.insert(node, idx);
+  idx
+}
+
 fn main() {
+  let http_version = match std::env::args().nth(1).as_deref() {
+    Some("http1") => HttpVersion::Http1,
+    _ => HttpVersion::Http2
+  };
+  let config = BundlerConfig::for_http_version(http_version);
   let (g, entries) = build_graph();
   println!("{:?}", Dot::new(&g));
 
   let mut bundle_roots = HashMap::new();
-  let mut reachable_bundles = HashSet::new();
+  let mut root_ids: Vec<NodeIndex> = Vec::new();
+  let mut root_index: HashMap<NodeIndex, usize> = HashMap::new();
+  let mut reachable_bundle_pairs: Vec<(usize, usize)> = Vec::new();
+  let mut async_bundles: Vec<(NodeIndex, usize, NodeIndex, NodeIndex)> = Vec::new();
+  let mut isolated_roots: HashSet<NodeIndex> = HashSet::new();
   let mut bundle_graph = Graph::new();
+  let mut dependency_bundle_graph = DependencyBundleGraph::default();
 
   // Step 1: Create bundles at the explicit split points in the graph.
   // Create bundles for each entry.
   for entry in &entries {
     let bundle_id = bundle_graph.add_node(Bundle::from_asset(*entry, &g[*entry]));
     bundle_roots.insert(*entry, (bundle_id, bundle_id));
+    assign_root_index(&mut root_ids, &mut root_index, *entry);
   }
-  
+
   // Traverse the asset graph and create bundles for asset type changes and async dependencies.
   // This only adds the entry asset of each bundle, not the subgraph.
   let mut stack = LinkedList::new();
@@ -75,15 +239,31 @@ fn main() {
         let asset_a = &g[u];
         let asset_b = &g[v];
 
-        // Create a new bundle when the asset type changes.
-        if asset_a.asset_type != asset_b.asset_type {
+        // Isolated assets (web workers, separate HTML entries, ...) get
+        // their own bundle and group and must not share code with their
+        // referencing bundle; this also makes Step 2's reachability DFS
+        // prune at `v`, the same as any other bundle root.
+        if asset_b.bundle_behavior == BundleBehavior::Isolated {
+          let bundle_id = bundle_graph.add_node(Bundle::from_asset(v, &g[v]));
+          bundle_roots.insert(v, (bundle_id, bundle_id));
+          assign_root_index(&mut root_ids, &mut root_index, v);
+          isolated_roots.insert(v);
+          dependency_bundle_graph.add_reference(u, v, bundle_id, ReferenceKind::Isolated);
+          return
+        }
+
+        // Create a new bundle when the asset type changes, unless the
+        // asset is meant to be inlined into the referencing bundle instead.
+        if asset_a.asset_type != asset_b.asset_type && asset_b.bundle_behavior != BundleBehavior::Inline {
           let (_, bundle_group_id) = stack.front().unwrap();
           let bundle_id = bundle_graph.add_node(Bundle::from_asset(v, &g[v]));
           bundle_roots.insert(v, (bundle_id, *bundle_group_id));
+          assign_root_index(&mut root_ids, &mut root_index, v);
 
           // Add an edge from the bundle group entry to the new bundle.
           // This indicates that the bundle is loaded together with the entry.
           bundle_graph.add_edge(*bundle_group_id, bundle_id, 0);
+          dependency_bundle_graph.add_reference(u, v, bundle_id, ReferenceKind::Sync);
           return
         }
 
@@ -92,6 +272,7 @@ fn main() {
         if dependency.is_async {
           let bundle_id = bundle_graph.add_node(Bundle::from_asset(v, &g[v]));
           bundle_roots.insert(v, (bundle_id, bundle_id));
+          let v_idx = assign_root_index(&mut root_ids, &mut root_index, v);
 
           // Walk up the stack until we hit a different asset type
           // and mark each this bundle as reachable from every parent bundle.
@@ -100,8 +281,41 @@ fn main() {
             if a.asset_type != asset_b.asset_type {
               break
             }
-            reachable_bundles.insert((*b, v));
+            reachable_bundle_pairs.push((root_index[b], v_idx));
           }
+
+          // Remember which bundle hosted this dependency, so Step 4 can
+          // check whether the async split is actually necessary.
+          let (parent_root, _) = *stack.front().unwrap();
+          async_bundles.push((v, v_idx, parent_root, bundle_id));
+          dependency_bundle_graph.add_reference(u, v, bundle_id, ReferenceKind::Async);
+        }
+      }
+      // A second (or later) edge into a node that already has its own bundle,
+      // e.g. two different entries dynamically importing the same module.
+      // `depth_first_search` only reports `TreeEdge` for the edge that first
+      // discovers a node, so this is the only place these land; without it
+      // the later import site would never get a `dependency_bundle_graph`
+      // reference, and Step 4's "every group that could load this bundle"
+      // check would never see the later parent either.
+      DfsEvent::BackEdge(u, v) | DfsEvent::CrossForwardEdge(u, v) => {
+        if let Some((bundle_id, _)) = bundle_roots.get(&v) {
+          let bundle_id = *bundle_id;
+          let asset_a = &g[u];
+          let asset_b = &g[v];
+          let (_, bundle_group_id) = *stack.front().unwrap();
+          bundle_graph.add_edge(bundle_group_id, bundle_id, 0);
+
+          let kind = if asset_b.bundle_behavior == BundleBehavior::Isolated {
+            ReferenceKind::Isolated
+          } else if asset_a.asset_type != asset_b.asset_type && asset_b.bundle_behavior != BundleBehavior::Inline {
+            ReferenceKind::Sync
+          } else if g.find_edge(u, v).is_some_and(|e| g[e].is_async) {
+            ReferenceKind::Async
+          } else {
+            ReferenceKind::Sync
+          };
+          dependency_bundle_graph.add_reference(u, v, bundle_id, kind);
         }
       }
       DfsEvent::Finish(n, _) => {
@@ -112,18 +326,28 @@ fn main() {
           }
         }
       }
-      _ => {}
     }
   });
 
+  // Pack the (ancestor, async root) pairs gathered above into a bitset per
+  // root: `dominated_by[v]` holds the dense indices of the roots that
+  // already guarantee `v` is reachable, so Step 3's "reachable in a parent"
+  // filter becomes a word-at-a-time AND instead of a HashSet scan.
+  let num_roots = root_ids.len();
+  let mut dominated_by: Vec<BitSet> = (0..num_roots).map(|_| BitSet::new(num_roots)).collect();
+  for (ancestor, v) in reachable_bundle_pairs {
+    dominated_by[v].insert(ancestor);
+  }
+
   println!("roots {:?}", bundle_roots);
-  println!("reachable {:?}", reachable_bundles);
+  println!("reachable {:?}", dominated_by);
   println!("initial bundle graph {:?}", Dot::new(&bundle_graph));
 
-  // Step 2: Determine reachability for every asset from each bundle root.
-  // This is later used to determine which bundles to place each asset in.
-  let mut reachable_nodes = HashSet::new();
+  // Step 2: Determine reachability for every asset from each bundle root, as
+  // a bitset of root indices rather than a HashSet of (root, node) pairs.
+  let mut asset_reachable: HashMap<NodeIndex, BitSet> = HashMap::new();
   for (root, _) in &bundle_roots {
+    let root_idx = root_index[root];
     depth_first_search(&g, Some(*root), |event| {
       if let DfsEvent::Discover(n, _) = &event {
         if n == root {
@@ -135,43 +359,63 @@ fn main() {
           return Control::<()>::Prune;
         }
 
-        reachable_nodes.insert((*root, *n));
+        asset_reachable.entry(*n).or_insert_with(|| BitSet::new(num_roots)).insert(root_idx);
       }
       Control::Continue
     });
   }
 
-  let reachable_graph = Graph::<(), ()>::from_edges(&reachable_nodes);
-  println!("{:?}", Dot::new(&reachable_graph));
+  println!("{:?}", asset_reachable);
 
   // Step 3: Place all assets into bundles. Each asset is placed into a single
   // bundle based on the bundle entries it is reachable from. This creates a
   // maximally code split bundle graph with no duplication.
 
-  // Create a mapping from entry asset ids to bundle ids.
-  let mut bundles: HashMap<Vec<NodeIndex>, NodeIndex> = HashMap::new();
+  // Create a mapping from the bitset of reachable root indices to bundle ids.
+  let mut bundles: HashMap<BitSet, NodeIndex> = HashMap::new();
 
   for asset_id in g.node_indices() {
     // Find bundle entries reachable from the asset.
-    let reachable: Vec<NodeIndex> = reachable_graph.neighbors_directed(asset_id, Incoming).collect();
+    let reachable_bits = asset_reachable.get(&asset_id).cloned().unwrap_or_else(|| BitSet::new(num_roots));
 
     // Filter out bundles when the asset is reachable in a parent bundle.
-    let reachable: Vec<NodeIndex> = reachable.iter().cloned().filter(|b| {
-      (&reachable).into_iter().all(|a| !reachable_bundles.contains(&(*a, *b)))
-    }).collect();
+    let mut reachable = reachable_bits.clone();
+    for b in reachable_bits.iter() {
+      if reachable_bits.intersects(&dominated_by[b]) {
+        reachable.remove(b);
+      }
+    }
+
+    // Isolated bundle roots never participate in hoisting: an asset reached
+    // through one is duplicated directly into it instead of being placed in
+    // a shared bundle with the other roots that reach it. This is the one
+    // case in which Step 3 places the same asset in more than one bundle.
+    for a in reachable_bits.iter() {
+      let root = root_ids[a];
+      if isolated_roots.contains(&root) {
+        reachable.remove(a);
+        if root != asset_id {
+          let isolated_bundle_id = bundle_roots[&root].0;
+          let bundle = &mut bundle_graph[isolated_bundle_id];
+          bundle.asset_ids.push(asset_id);
+          bundle.size += g[asset_id].size;
+        }
+      }
+    }
 
     if let Some((bundle_id, _)) = bundle_roots.get(&asset_id) {
       // If the asset is a bundle root, add the bundle to every other reachable bundle group.
-      bundles.entry(vec![asset_id]).or_insert(*bundle_id);
-      for a in &reachable {
-        if *a != asset_id {
-          bundle_graph.add_edge(bundle_roots[a].1, *bundle_id, 0);
+      bundles.entry(BitSet::singleton(num_roots, root_index[&asset_id])).or_insert(*bundle_id);
+      for a in reachable.iter() {
+        let root = root_ids[a];
+        if root != asset_id {
+          bundle_graph.add_edge(bundle_roots[&root].1, *bundle_id, 0);
         }
       }
-    } else if reachable.len() > 0 {
+    } else if !reachable.is_empty() {
       // If the asset is reachable from more than one entry, find or create
       // a bundle for that combination of entries, and add the asset to it.
-      let source_bundles = reachable.iter().map(|a| bundles[&vec![*a]]).collect();
+      let source_bundles = reachable.iter().map(|a| bundles[&BitSet::singleton(num_roots, a)]).collect();
       let bundle_id = bundles.entry(reachable.clone()).or_insert_with(|| {
         let mut bundle = Bundle::default();
         bundle.source_bundles = source_bundles;
@@ -181,28 +425,84 @@ fn main() {
       let bundle = &mut bundle_graph[*bundle_id];
       bundle.asset_ids.push(asset_id);
       bundle.size += g[asset_id].size;
+      if g[asset_id].bundle_behavior == BundleBehavior::Inline {
+        bundle.inline_asset_ids.push(asset_id);
+      }
 
       // Add the bundle to each reachable bundle group.
-      for a in reachable {
-        if a != *bundle_id {
-          bundle_graph.add_edge(bundle_roots[&a].1, *bundle_id, 0);
+      for a in reachable.iter() {
+        let root = root_ids[a];
+        if root != *bundle_id {
+          bundle_graph.add_edge(bundle_roots[&root].1, *bundle_id, 0);
         }
       }
     }
   }
 
-  // Step 4: Remove shared bundles that are smaller than the minimum size,
+  // Step 4: Internalize async dependencies whose target is already
+  // guaranteed to be loaded by the time every possible parent bundle group
+  // loads, so the dynamic import can resolve against code that's already
+  // present instead of fetching a redundant bundle.
+
+  // `bundle_graph`'s incoming neighbors of a bundle are bundle-group ids
+  // (`NodeIndex`es into `bundle_graph`), not asset roots, so they can't be
+  // looked up in `root_index` directly (that's keyed by asset-graph
+  // `NodeIndex`es, a different index space that just happens to share a
+  // type). Invert `bundle_roots` to map each group id back to the dense
+  // root index of the asset that created it. A group id is only meaningful
+  // for the entry that owns it (where the bundle is its own group), so only
+  // those entries may contribute a mapping; every other asset in the group
+  // shares that same group id and would otherwise overwrite it with an
+  // unrelated root index.
+  let group_root_index: HashMap<NodeIndex, usize> = bundle_roots.iter()
+    .filter(|(_, (bundle_id, group_id))| bundle_id == group_id)
+    .map(|(asset_root, (_, group_id))| (*group_id, root_index[asset_root]))
+    .collect();
+
+  // A target counts as already loaded by a group only if the group's root
+  // reaches it through synchronous dependencies alone.
+  let sync_graph = EdgeFiltered::from_fn(&g, |e| !e.weight().is_async);
+  for (v, _, parent_root, async_bundle_id) in async_bundles {
+    let (parent_bundle_id, parent_group_id) = bundle_roots[&parent_root];
+    let already_loaded = bundle_graph.neighbors_directed(parent_bundle_id, Incoming)
+      .chain(std::iter::once(parent_group_id))
+      .all(|group| group_root_index.get(&group).is_some_and(|idx| has_path_connecting(&sync_graph, root_ids[*idx], v, None)));
+
+    if already_loaded {
+      internalize_bundle(&g, &mut bundle_graph, &mut dependency_bundle_graph, parent_bundle_id, async_bundle_id, v);
+      bundle_roots.remove(&v);
+    }
+  }
+
+  // Step 5: Remove shared bundles that aren't referenced from enough distinct
+  // bundle groups to be worth the extra request, merging their assets back
+  // into the source bundles that would have held them otherwise.
+  // `remove_bundle` shrinks the graph via petgraph's swap-remove, which can
+  // relocate not-yet-visited nodes to already-visited indices (or invalidate
+  // the last index entirely). A list of ids fixed before any removal doesn't
+  // protect against that once more than one bundle qualifies, so re-scan the
+  // live graph for the next candidate on every pass instead.
+  while let Some(bundle_id) = bundle_graph.node_indices().find(|&id| {
+    let bundle = &bundle_graph[id];
+    bundle.source_bundles.len() > 0
+      && bundle_graph.neighbors_directed(id, Incoming).collect::<HashSet<_>>().len() < config.min_bundles
+  }) {
+    remove_bundle(&g, &mut bundle_graph, &mut dependency_bundle_graph, bundle_id);
+  }
+
+  // Step 6: Remove shared bundles that are smaller than the minimum size,
   // and add the assets to the original source bundles they were referenced from.
   // This may result in duplication of assets in multiple bundles.
-  for bundle_id in bundle_graph.node_indices() {
-    let bundle = &bundle_graph[bundle_id];
-    if bundle.source_bundles.len() > 0 && bundle.size < 10 {
-      remove_bundle(&g, &mut bundle_graph, bundle_id);
-    }
+  // Re-scan the live graph per removal, for the same reason as Step 5.
+  while let Some(bundle_id) = bundle_graph.node_indices().find(|&id| {
+    let bundle = &bundle_graph[id];
+    bundle.source_bundles.len() > 0 && bundle.size < config.min_bundle_size
+  }) {
+    remove_bundle(&g, &mut bundle_graph, &mut dependency_bundle_graph, bundle_id);
   }
 
-  // Step 5: Remove shared bundles from bundle groups that hit the parallel request limit.
-  let limit = 3;
+  // Step 7: Remove shared bundles from bundle groups that hit the parallel request limit.
+  let limit = config.max_parallel_requests;
   for (_, (bundle_id, bundle_group_id)) in bundle_roots {
     // Only handle bundle group entries.
     if bundle_id != bundle_group_id {
@@ -221,7 +521,7 @@ fn main() {
         let source_bundles: Vec<NodeIndex> = bundle_graph[*bundle_id].source_bundles.drain_filter(|s| neighbors.contains(s)).collect();
         for source in source_bundles {
           for asset_id in bundle_graph[*bundle_id].asset_ids.clone() {
-            let bundle_id = bundles[&vec![source]];
+            let bundle_id = bundles[&BitSet::singleton(num_roots, root_index[&source])];
             let bundle = &mut bundle_graph[bundle_id];
             bundle.asset_ids.push(asset_id);
             bundle.size += g[asset_id].size;
@@ -235,9 +535,9 @@ fn main() {
         // merge it into the remaining source bundles. If it is orphaned entirely, remove it.
         let count = bundle_graph.neighbors_directed(*bundle_id, Incoming).count();
         if count == 1 {
-          remove_bundle(&g, &mut bundle_graph, *bundle_id);
+          remove_bundle(&g, &mut bundle_graph, &mut dependency_bundle_graph, *bundle_id);
         } else if count == 0 {
-          bundle_graph.remove_node(*bundle_id);
+          remove_bundle_node(&mut bundle_graph, &mut dependency_bundle_graph, *bundle_id, None);
         }
       }
     }
@@ -247,16 +547,89 @@ fn main() {
 
   for bundle_id in bundle_graph.node_indices() {
     let bundle = &bundle_graph[bundle_id];
-    println!("{} {}", bundle.asset_ids.iter().map(|n| g[*n].name).collect::<Vec<&str>>().join(", "), bundle.size)
+    println!("{} {}", bundle.asset_ids.iter().map(|n| g[*n].name).collect::<Vec<&str>>().join(", "), bundle.size);
+    if !bundle.internalized_asset_ids.is_empty() {
+      println!(
+        "  internalized: {}",
+        bundle.internalized_asset_ids.iter().map(|n| g[*n].name).collect::<Vec<&str>>().join(", ")
+      );
+    }
+    if !bundle.inline_asset_ids.is_empty() {
+      println!(
+        "  inlined: {}",
+        bundle.inline_asset_ids.iter().map(|n| g[*n].name).collect::<Vec<&str>>().join(", ")
+      );
+    }
+  }
+
+  // Expose the dependency -> bundle reference graph alongside the bundle
+  // list, so a packager can look up exactly which bundle(s) to reference
+  // at each import site instead of re-deriving it from the asset graph.
+  println!("dependency bundle graph:");
+  for (u, v, bundle_id, kind) in &dependency_bundle_graph.references {
+    println!("  {} -> {} : {} ({:?})", g[*u].name, g[*v].name, bundle_graph[*bundle_id].asset_ids.iter().map(|n| g[*n].name).collect::<Vec<&str>>().join(", "), kind);
   }
 }
 
+// Removes `bundle_id` from `bundle_graph`, keeping `dependency_bundle_graph`
+// consistent with petgraph's swap-remove: the bundle that used to sit at the
+// last index now lives at `bundle_id`, so any reference pointing at it must
+// be retargeted there. References that pointed at `bundle_id` itself are
+// retargeted to `retarget_to` if given, or dropped if the removed bundle has
+// no single successor for packagers to point at instead.
+fn remove_bundle_node(
+  bundle_graph: &mut Graph<Bundle, i32>,
+  dependency_bundle_graph: &mut DependencyBundleGraph,
+  bundle_id: NodeIndex,
+  retarget_to: Option<NodeIndex>
+) -> Bundle {
+  let last_id = NodeIndex::new(bundle_graph.node_count() - 1);
+  let mut bundle = bundle_graph.remove_node(bundle_id).unwrap();
+
+  match retarget_to {
+    Some(target) => dependency_bundle_graph.retarget(bundle_id, target),
+    None => dependency_bundle_graph.references.retain(|r| r.2 != bundle_id)
+  }
+
+  if last_id != bundle_id {
+    dependency_bundle_graph.retarget(last_id, bundle_id);
+
+    // Bundles also hold raw `NodeIndex`es into `bundle_graph` in their own
+    // `source_bundles`, which are just as subject to the swap-remove as the
+    // dependency graph is. Fix up both the bundles still in the graph and
+    // the one we just detached, since callers read its `source_bundles` too.
+    for other in bundle_graph.node_weights_mut() {
+      for s in other.source_bundles.iter_mut() {
+        if *s == last_id {
+          *s = bundle_id;
+        }
+      }
+    }
+    for s in bundle.source_bundles.iter_mut() {
+      if *s == last_id {
+        *s = bundle_id;
+      }
+    }
+  }
+
+  bundle
+}
+
 fn remove_bundle(
   asset_graph: &Graph<Asset, Dependency>,
   bundle_graph: &mut Graph<Bundle, i32>,
+  dependency_bundle_graph: &mut DependencyBundleGraph,
   bundle_id: NodeIndex
 ) {
-  let bundle = bundle_graph.remove_node(bundle_id).unwrap();
+  // References to the removed bundle are repointed at its source bundle when
+  // it has exactly one. Otherwise they are dropped: a dissolved bundle's
+  // assets can scatter into more than one source bundle, and there is no
+  // single bundle for the reference graph to point at.
+  let retarget_to = match bundle_graph[bundle_id].source_bundles.as_slice() {
+    [only] => Some(*only),
+    _ => None
+  };
+  let bundle = remove_bundle_node(bundle_graph, dependency_bundle_graph, bundle_id, retarget_to);
   for asset_id in &bundle.asset_ids {
     for source_bundle_id in &bundle.source_bundles {
       let bundle = &mut bundle_graph[*source_bundle_id];
@@ -266,6 +639,26 @@ fn remove_bundle(
   }
 }
 
+// Folds an internalized async bundle's assets into the parent bundle that
+// already guarantees they're loaded, and notes the dependency's root asset
+// so packagers can rewrite the dynamic import against the parent instead.
+fn internalize_bundle(
+  asset_graph: &Graph<Asset, Dependency>,
+  bundle_graph: &mut Graph<Bundle, i32>,
+  dependency_bundle_graph: &mut DependencyBundleGraph,
+  parent_bundle_id: NodeIndex,
+  async_bundle_id: NodeIndex,
+  async_root: NodeIndex
+) {
+  let bundle = remove_bundle_node(bundle_graph, dependency_bundle_graph, async_bundle_id, Some(parent_bundle_id));
+  let parent = &mut bundle_graph[parent_bundle_id];
+  parent.internalized_asset_ids.push(async_root);
+  for asset_id in bundle.asset_ids {
+    parent.asset_ids.push(asset_id);
+    parent.size += asset_graph[asset_id].size;
+  }
+}
+
 fn build_graph<'a>() -> (Graph<Asset<'a>, Dependency>, Vec<NodeIndex>) {
   let mut g = Graph::new();
   let mut entries = Vec::new();
@@ -273,49 +666,102 @@ fn build_graph<'a>() -> (Graph<Asset<'a>, Dependency>, Vec<NodeIndex>) {
   let html = g.add_node(Asset {
     name: "a.html",
     asset_type: AssetType::HTML,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let html2 = g.add_node(Asset {
     name: "b.html",
     asset_type: AssetType::HTML,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let js = g.add_node(Asset {
     name: "a.js",
     asset_type: AssetType::JavaScript,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let js2 = g.add_node(Asset {
     name: "async.js",
     asset_type: AssetType::JavaScript,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let js3 = g.add_node(Asset {
     name: "async2.js",
     asset_type: AssetType::JavaScript,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
+  });
+
+  // A dynamic import reached only through another dynamic import. No bundle
+  // group reaches it through synchronous dependencies, so Step 4 keeps it in
+  // its own bundle instead of internalizing it.
+  let js_deferred = g.add_node(Asset {
+    name: "deferred.js",
+    asset_type: AssetType::JavaScript,
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let js4 = g.add_node(Asset {
     name: "b.js",
     asset_type: AssetType::JavaScript,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let js5 = g.add_node(Asset {
     name: "shared.js",
     asset_type: AssetType::JavaScript,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   let css = g.add_node(Asset {
     name: "styles.css",
     asset_type: AssetType::CSS,
-    size: 10
+    size: 10,
+    bundle_behavior: BundleBehavior::None
+  });
+
+  // CSS text meant to be inlined into the JS bundle that imports it (e.g. a
+  // `import css from './inline.css'` string import) rather than emitted as
+  // its own file.
+  let css_inline = g.add_node(Asset {
+    name: "inline.css",
+    asset_type: AssetType::CSS,
+    size: 10,
+    bundle_behavior: BundleBehavior::Inline
+  });
+
+  // Each entry has its own web worker, which must not share a bundle with
+  // the page that spawns it. Both workers pull in the same helper, which
+  // should end up duplicated into each isolated bundle rather than hoisted
+  // into a shared one.
+  let js_worker_a = g.add_node(Asset {
+    name: "a-worker.js",
+    asset_type: AssetType::JavaScript,
+    size: 10,
+    bundle_behavior: BundleBehavior::Isolated
+  });
+
+  let js_worker_b = g.add_node(Asset {
+    name: "b-worker.js",
+    asset_type: AssetType::JavaScript,
+    size: 10,
+    bundle_behavior: BundleBehavior::Isolated
+  });
+
+  let js_worker_shared = g.add_node(Asset {
+    name: "worker-shared.js",
+    asset_type: AssetType::JavaScript,
+    size: 10,
+    bundle_behavior: BundleBehavior::None
   });
 
   g.add_edge(html, js, Dependency {
@@ -330,12 +776,24 @@ fn build_graph<'a>() -> (Graph<Asset<'a>, Dependency>, Vec<NodeIndex>) {
   g.add_edge(js2, js3, Dependency {
     is_async: false
   });
+  g.add_edge(js2, js_deferred, Dependency {
+    is_async: true
+  });
   g.add_edge(js3, js5, Dependency {
     is_async: false
   });
   g.add_edge(js, css, Dependency {
     is_async: false
   });
+  g.add_edge(js, css_inline, Dependency {
+    is_async: false
+  });
+  g.add_edge(js, js_worker_a, Dependency {
+    is_async: false
+  });
+  g.add_edge(js_worker_a, js_worker_shared, Dependency {
+    is_async: false
+  });
 
   g.add_edge(html2, js4, Dependency {
     is_async: false
@@ -344,7 +802,20 @@ fn build_graph<'a>() -> (Graph<Asset<'a>, Dependency>, Vec<NodeIndex>) {
   g.add_edge(js4, js5, Dependency {
     is_async: false
   });
-  
+  g.add_edge(js4, js_worker_b, Dependency {
+    is_async: false
+  });
+  g.add_edge(js_worker_b, js_worker_shared, Dependency {
+    is_async: false
+  });
+
+  // b.js dynamically imports the same module a.js already imports
+  // asynchronously. a.html's subtree discovers and finishes async.js first,
+  // so this second import site never gets a `TreeEdge` event.
+  g.add_edge(js4, js2, Dependency {
+    is_async: true
+  });
+
   entries.push(html);
   entries.push(html2);
 